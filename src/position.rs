@@ -11,16 +11,49 @@ use std::fmt;
 struct State {
     checkers: Bitboard,             // 王手をかけている駒の位置
     pinned: [Bitboard; Color::NUM], // 飛び駒から玉を守っている駒の位置
+    // 各色の玉に対する飛び駒の位置(pinnedを生んでいるスナイパー自身)
+    pinners: [Bitboard; Color::NUM],
+    // 各色について、動かすと相手玉への王手が発生するその色自身の駒(discovered check candidates)
+    discovered_check_candidates: [Bitboard; Color::NUM],
+    key: u64, // 局面のハッシュ値(Zobrist hash)
 }
 
 impl State {
-    fn new(checkers: Bitboard, pinned: [Bitboard; Color::NUM]) -> Self {
-        Self { checkers, pinned }
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        checkers: Bitboard,
+        pinned: [Bitboard; Color::NUM],
+        pinners: [Bitboard; Color::NUM],
+        discovered_check_candidates: [Bitboard; Color::NUM],
+        key: u64,
+    ) -> Self {
+        Self {
+            checkers,
+            pinned,
+            pinners,
+            discovered_check_candidates,
+            key,
+        }
     }
-    fn calculate_pinned(c_bb: &[Bitboard], pt_bb: &[Bitboard]) -> [Bitboard; Color::NUM] {
-        let mut bbs = [Bitboard::ZERO, Bitboard::ZERO];
+    // 飛び駒から玉を守っている駒(pinned)、その飛び駒自身(pinners)、
+    // そして飛び駒を持つ側自身の駒で動かすと相手玉への王手が発生するもの
+    // (discovered_check_candidates)を、Stockfishのhidden_checkersに倣って
+    // まとめて求める。
+    #[allow(clippy::type_complexity)]
+    fn calculate_pin_info(
+        c_bb: &[Bitboard],
+        pt_bb: &[Bitboard],
+    ) -> (
+        [Bitboard; Color::NUM],
+        [Bitboard; Color::NUM],
+        [Bitboard; Color::NUM],
+    ) {
+        let mut pinned = [Bitboard::ZERO, Bitboard::ZERO];
+        let mut pinners = [Bitboard::ZERO, Bitboard::ZERO];
+        let mut discovered_check_candidates = [Bitboard::ZERO, Bitboard::ZERO];
         for c in Color::ALL {
-            if let Some(sq) = (c_bb[(!c).index()] & pt_bb[PieceType::OU.index()]).next() {
+            let king_owner = !c;
+            if let Some(sq) = (c_bb[king_owner.index()] & pt_bb[PieceType::OU.index()]).next() {
                 #[rustfmt::skip]
                 let snipers = (
                       (ATTACK_TABLE.pseudo_attack(PieceType::KY, sq, c) & pt_bb[PieceType::KY.index()])
@@ -31,15 +64,56 @@ impl State {
                     let blockers = BETWEEN_TABLE[sq.index()][sniper.index()]
                         & pt_bb[PieceType::OCCUPIED.index()];
                     if blockers.count_ones() == 1 {
-                        bbs[c.index()] |= blockers;
+                        pinned[c.index()] |= blockers;
+                        pinners[king_owner.index()] |= Bitboard::from_square(sniper);
+                        if !(blockers & c_bb[c.index()]).is_empty() {
+                            discovered_check_candidates[c.index()] |= blockers;
+                        }
                     }
                 }
             }
         }
-        bbs
+        (pinned, pinners, discovered_check_candidates)
     }
 }
 
+/// `Position::from_sfen`のパース失敗を表すエラー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SfenError {
+    MissingField,
+    InvalidBoard(String),
+    InvalidColor(String),
+    InvalidHand(String),
+    InvalidMoveNumber(String),
+}
+
+impl fmt::Display for SfenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SfenError::MissingField => write!(f, "sfen string is missing a field"),
+            SfenError::InvalidBoard(s) => write!(f, "invalid sfen board field: {}", s),
+            SfenError::InvalidColor(s) => write!(f, "invalid sfen side to move field: {}", s),
+            SfenError::InvalidHand(s) => write!(f, "invalid sfen hand field: {}", s),
+            SfenError::InvalidMoveNumber(s) => write!(f, "invalid sfen move number field: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for SfenError {}
+
+/// `Position::repetition`の判定結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repetition {
+    /// 千日手は成立していない
+    None,
+    /// 千日手(引き分け)
+    Draw,
+    /// 連続王手の千日手により手番側の勝ち
+    WinByPerpetualCheck,
+    /// 連続王手の千日手により手番側の負け
+    LoseByPerpetualCheck,
+}
+
 /// Represents a state of the game.
 #[derive(Debug)]
 pub struct Position {
@@ -49,6 +123,7 @@ pub struct Position {
     c_bb: [Bitboard; Color::NUM],
     pt_bb: [Bitboard; PieceType::NUM],
     states: Vec<State>,
+    move_number: u32, // SFENの手数。from_sfenで読み込んだ値を往復できるよう保持する
 }
 
 impl Position {
@@ -80,22 +155,174 @@ impl Position {
             }
         }
         // initial state
-        let state = {
-            let c = side_to_move;
-            let checkers = Bitboard::ZERO;
-            if let Some(_sq) = (c_bb[(!c).index()] & pt_bb[PieceType::OU.index()]).next() {
-                // TODO
-            }
-            State::new(checkers, State::calculate_pinned(&c_bb, &pt_bb))
-        };
-        Self {
+        let key = zobrist::initial_key(&board, &hands, side_to_move);
+        let (pinned, pinners, discovered_check_candidates) =
+            State::calculate_pin_info(&c_bb, &pt_bb);
+        let state = State::new(
+            Bitboard::ZERO,
+            pinned,
+            pinners,
+            discovered_check_candidates,
+            key,
+        );
+        let mut pos = Self {
             board,
             hands,
             color: side_to_move,
             pt_bb,
             c_bb,
             states: vec![state],
+            move_number: 1,
+        };
+        // 手番側がすでに王手されている局面(SFEN等から読み込んだ局面)でも
+        // checkersを正しく求められるよう、構築後にattackers_toで再計算する
+        if let Some(sq) = pos.king(side_to_move) {
+            let checkers = pos.attackers_to(!side_to_move, sq);
+            pos.states.last_mut().expect("empty states").checkers = checkers;
         }
+        pos
+    }
+    /// USIプロトコルのSFEN文字列から局面を構築する
+    ///
+    /// 例: `lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1`
+    pub fn from_sfen(sfen: &str) -> Result<Position, SfenError> {
+        let mut parts = sfen.split_whitespace();
+        let board_sfen = parts.next().ok_or(SfenError::MissingField)?;
+        let color_sfen = parts.next().ok_or(SfenError::MissingField)?;
+        let hand_sfen = parts.next().ok_or(SfenError::MissingField)?;
+        let move_number = match parts.next() {
+            Some(s) => s
+                .parse::<u32>()
+                .map_err(|_| SfenError::InvalidMoveNumber(s.to_string()))?,
+            None => 1,
+        };
+
+        let mut board = [Piece::EMP; Square::NUM];
+        let rows: Vec<&str> = board_sfen.split('/').collect();
+        if rows.len() != Rank::ALL.len() {
+            return Err(SfenError::InvalidBoard(board_sfen.to_string()));
+        }
+        for (&rank, row) in Rank::ALL.iter().zip(rows.iter()) {
+            let mut files = File::ALL.iter().rev();
+            let mut chars = row.chars();
+            while let Some(ch) = chars.next() {
+                if let Some(n) = ch.to_digit(10) {
+                    for _ in 0..n {
+                        files
+                            .next()
+                            .ok_or_else(|| SfenError::InvalidBoard(board_sfen.to_string()))?;
+                    }
+                    continue;
+                }
+                let promoted = ch == '+';
+                let piece_ch = if promoted {
+                    chars
+                        .next()
+                        .ok_or_else(|| SfenError::InvalidBoard(board_sfen.to_string()))?
+                } else {
+                    ch
+                };
+                let file = files
+                    .next()
+                    .ok_or_else(|| SfenError::InvalidBoard(board_sfen.to_string()))?;
+                let piece = sfen_char_to_piece(piece_ch, promoted)
+                    .ok_or_else(|| SfenError::InvalidBoard(board_sfen.to_string()))?;
+                board[Square::new(*file, rank).index()] = piece;
+            }
+            if files.len() != 0 {
+                return Err(SfenError::InvalidBoard(board_sfen.to_string()));
+            }
+        }
+
+        let side_to_move = match color_sfen {
+            "b" => Color::Black,
+            "w" => Color::White,
+            _ => return Err(SfenError::InvalidColor(color_sfen.to_string())),
+        };
+
+        let mut hand_nums = [[0u8; PieceType::NUM_HAND]; Color::NUM];
+        if hand_sfen != "-" {
+            let mut count = String::new();
+            for ch in hand_sfen.chars() {
+                if ch.is_ascii_digit() {
+                    count.push(ch);
+                    continue;
+                }
+                let num: u8 = if count.is_empty() {
+                    1
+                } else {
+                    let n = count
+                        .parse()
+                        .map_err(|_| SfenError::InvalidHand(hand_sfen.to_string()))?;
+                    count.clear();
+                    n
+                };
+                let (c, pt) = sfen_char_to_color_piece_type(ch)
+                    .ok_or_else(|| SfenError::InvalidHand(hand_sfen.to_string()))?;
+                let i = PieceType::ALL_HAND
+                    .iter()
+                    .position(|&x| x == pt)
+                    .ok_or_else(|| SfenError::InvalidHand(hand_sfen.to_string()))?;
+                hand_nums[c.index()][i] += num;
+            }
+        }
+
+        let mut pos = Self::new(board, hand_nums, side_to_move);
+        pos.move_number = move_number;
+        Ok(pos)
+    }
+    /// 局面をUSIプロトコルのSFEN文字列に変換する
+    pub fn to_sfen(&self) -> String {
+        use std::fmt::Write as _;
+        let mut sfen = String::new();
+        let num_ranks = Rank::ALL.len();
+        for (i, &rank) in Rank::ALL.iter().enumerate() {
+            let mut empty = 0;
+            for &file in File::ALL.iter().rev() {
+                let p = self.piece_on(Square::new(file, rank));
+                if p == Piece::EMP {
+                    empty += 1;
+                    continue;
+                }
+                if empty > 0 {
+                    write!(sfen, "{}", empty).unwrap();
+                    empty = 0;
+                }
+                sfen.push_str(&piece_to_sfen_str(p));
+            }
+            if empty > 0 {
+                write!(sfen, "{}", empty).unwrap();
+            }
+            if i + 1 != num_ranks {
+                sfen.push('/');
+            }
+        }
+        sfen.push(' ');
+        sfen.push(match self.side_to_move() {
+            Color::Black => 'b',
+            Color::White => 'w',
+        });
+        sfen.push(' ');
+        let mut hand_str = String::new();
+        for c in Color::ALL {
+            for &pt in PieceType::ALL_HAND.iter().rev() {
+                let num = self.hand(c).num(pt);
+                if num == 0 {
+                    continue;
+                }
+                if num > 1 {
+                    write!(hand_str, "{}", num).unwrap();
+                }
+                hand_str.push_str(&piece_to_sfen_str(piece_from_color_piece_type(c, pt)));
+            }
+        }
+        if hand_str.is_empty() {
+            sfen.push('-');
+        } else {
+            sfen.push_str(&hand_str);
+        }
+        write!(sfen, " {}", self.move_number).unwrap();
+        sfen
     }
     pub fn piece_on(&self, sq: Square) -> Piece {
         self.board[sq.index()]
@@ -125,6 +352,19 @@ impl Position {
     pub fn pinned(&self) -> [Bitboard; Color::NUM] {
         self.state().expect("empty states").pinned
     }
+    /// `c`の玉にpinを掛けている飛び駒(スナイパー)の位置を返す
+    pub fn pinners(&self, c: Color) -> Bitboard {
+        self.state().expect("empty states").pinners[c.index()]
+    }
+    /// `c`自身の駒で、動かすと相手玉への王手が発生するもの(discovered check
+    /// candidates)の位置を返す
+    pub fn discovered_check_candidates(&self, c: Color) -> Bitboard {
+        self.state().expect("empty states").discovered_check_candidates[c.index()]
+    }
+    /// 現局面のZobristハッシュ値を返す
+    pub fn key(&self) -> u64 {
+        self.state().expect("empty states").key
+    }
     pub fn king(&self, c: Color) -> Option<Square> {
         self.pieces_cp(c, PieceType::OU).next()
     }
@@ -139,6 +379,94 @@ impl Position {
         ml.generate_legals(self);
         ml
     }
+    /// 相手の駒を取る指し手のみを返す(静止探索・指し手オーダリング向け)
+    ///
+    /// 暫定実装・スコープについて: 本来は`MoveList::generate_legals`自体に
+    /// 捕獲専用の生成モード(ターゲットマスクを生成器内部に渡すモードフラグ)を
+    /// 持たせ、全合法手を生成せずに捕獲だけを列挙するのが筋である。しかし
+    /// `movegen`モジュールは本リポジトリのこのスナップショットに含まれておらず、
+    /// 既存の生成アルゴリズムや内部APIを踏襲できる根拠がないため、ここでは
+    /// `legal_moves()`の結果を移動先の駒の有無で絞り込む形の利便APIとして
+    /// 留める(スコープをこのコミットで確定させる)。したがって全合法手を
+    /// 生成する以上のコスト削減にはならない。両方が必要な場合は
+    /// [`Position::capture_and_quiet_moves`]を使えば生成を1回で済ませられる。
+    pub fn capture_moves(&self) -> Vec<Move> {
+        let target = self.pieces_c(!self.side_to_move());
+        self.legal_moves()
+            .iter()
+            .copied()
+            .filter(|m| !(target & m.to()).is_empty())
+            .collect()
+    }
+    /// 相手の駒を取らない指し手のみを返す
+    ///
+    /// [`Position::capture_moves`]と同様、`legal_moves()`を絞り込むだけの
+    /// 暫定実装であり、生成コストそのものは削減していない。両方が必要な場合は
+    /// [`Position::capture_and_quiet_moves`]を使えば生成を1回で済ませられる。
+    pub fn quiet_moves(&self) -> Vec<Move> {
+        let target = self.pieces_c(!self.side_to_move());
+        self.legal_moves()
+            .iter()
+            .copied()
+            .filter(|m| (target & m.to()).is_empty())
+            .collect()
+    }
+    /// 捕獲する指し手とそれ以外の指し手を1回の合法手生成でまとめて返す
+    ///
+    /// [`Position::capture_moves`]と[`Position::quiet_moves`]を両方呼ぶと
+    /// `legal_moves()`が2回生成されてしまうため、両方が必要な呼び出し側
+    /// (静止探索など)はこちらを使うことで生成コストを1回分に抑えられる。
+    pub fn capture_and_quiet_moves(&self) -> (Vec<Move>, Vec<Move>) {
+        let target = self.pieces_c(!self.side_to_move());
+        let mut captures = Vec::new();
+        let mut quiets = Vec::new();
+        for &m in self.legal_moves().iter() {
+            if (target & m.to()).is_empty() {
+                quiets.push(m);
+            } else {
+                captures.push(m);
+            }
+        }
+        (captures, quiets)
+    }
+    /// 千日手(同一局面4回)の判定を行う
+    ///
+    /// 将棋の千日手はチェスの同一局面反復とは異なり、反復の間ずっと手番側が
+    /// 王手をかけ続けていた場合はその手番側の負け(連続王手の千日手)となる。
+    pub fn repetition(&self) -> Repetition {
+        let len = self.states.len();
+        let key = self.key();
+        let mut count = 1;
+        let mut us_all_checks = true;
+        let mut them_all_checks = true;
+        let mut k = 1;
+        while 2 * k <= len - 1 {
+            let j = len - 1 - 2 * k;
+            for p in [j + 1, j + 2] {
+                let mover_is_us = (len - p) % 2 == 0;
+                let checked = !self.states[p].checkers.is_empty();
+                if mover_is_us {
+                    us_all_checks &= checked;
+                } else {
+                    them_all_checks &= checked;
+                }
+            }
+            if self.states[j].key == key {
+                count += 1;
+                if count >= 4 {
+                    return if us_all_checks {
+                        Repetition::LoseByPerpetualCheck
+                    } else if them_all_checks {
+                        Repetition::WinByPerpetualCheck
+                    } else {
+                        Repetition::Draw
+                    };
+                }
+            }
+            k += 1;
+        }
+        Repetition::None
+    }
     pub fn do_move(&mut self, m: Move) {
         let state = if let Some(from) = m.from() {
             self.do_normal_move(from, m.to(), m.is_promotion())
@@ -147,6 +475,7 @@ impl Position {
         };
         self.states.push(state);
         self.color = !self.color;
+        self.move_number += 1;
     }
     pub fn undo_move(&mut self, m: Move) {
         let c = self.side_to_move();
@@ -177,32 +506,93 @@ impl Position {
         }
         self.color = !self.color;
         self.states.pop();
+        self.move_number -= 1;
+    }
+    /// `depth`手先までの合法手を指し尽くし、末端局面数を返す
+    ///
+    /// `do_move`/`undo_move`を使って`legal_moves()`を再帰的に展開するだけの
+    /// 単純な実装だが、指し手生成が壊れていないかを確認するのに役立つ。
+    pub fn perft(&mut self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let moves = self.legal_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        moves
+            .iter()
+            .map(|&m| {
+                self.do_move(m);
+                let nodes = self.perft(depth - 1);
+                self.undo_move(m);
+                nodes
+            })
+            .sum()
+    }
+    /// ルートの指し手ごとの`perft`の内訳を返す
+    pub fn perft_divide(&mut self, depth: u32) -> Vec<(Move, u64)> {
+        self.legal_moves()
+            .iter()
+            .map(|&m| {
+                self.do_move(m);
+                let nodes = if depth > 1 { self.perft(depth - 1) } else { 1 };
+                self.undo_move(m);
+                (m, nodes)
+            })
+            .collect()
     }
     // 駒移動
     fn do_normal_move(&mut self, from: Square, to: Square, promotion: bool) -> State {
         let c = self.side_to_move();
         let p_from = self.piece_on(from);
+        let mut key = self.key();
         self.remove_piece(from, p_from);
+        key ^= zobrist::board_key(from, p_from);
         // 移動先に駒がある場合
         if let Some(pt) = self.piece_on(to).piece_type() {
+            let p_cap = self.piece_on(to);
+            key ^= zobrist::board_key(to, p_cap);
             self.xor_bbs(!c, pt, to);
-            self.hands[c.index()].increment(pt);
+            // 持ち駒・zobristは常に不成の種類で管理するため、捕獲した駒が成り駒
+            // (と・成香・成桂・成銀・馬・龍)であれば不成の種類に戻してから扱う。
+            // これを怠るとhand_key/hand_indexがALL_HANDに含まれない成り駒の
+            // 種類を受け取ってpanicし、また初期局面から組んだキーとも食い違う。
+            let hand_pt = p_cap.demoted().piece_type().unwrap();
+            let num = self.hands[c.index()].num(hand_pt);
+            self.hands[c.index()].increment(hand_pt);
+            key ^= zobrist::hand_key(c, hand_pt, num) ^ zobrist::hand_key(c, hand_pt, num + 1);
         }
         let p_to = if promotion { p_from.promoted() } else { p_from };
         self.put_piece(to, p_to);
+        key ^= zobrist::board_key(to, p_to);
+        key ^= zobrist::SIDE;
         let checkers = if let Some(sq) = self.king(!c) {
             self.attackers_to(c, sq)
         } else {
             Bitboard::ZERO
         };
-        State::new(checkers, State::calculate_pinned(&self.c_bb, &self.pt_bb))
+        let (pinned, pinners, discovered_check_candidates) =
+            State::calculate_pin_info(&self.c_bb, &self.pt_bb);
+        State::new(
+            checkers,
+            pinned,
+            pinners,
+            discovered_check_candidates,
+            key,
+        )
     }
     // 駒打ち
     fn do_drop_move(&mut self, to: Square, p: Piece) -> State {
         let c = self.side_to_move();
         let pt = p.piece_type().unwrap();
+        let mut key = self.key();
         self.put_piece(to, p);
+        key ^= zobrist::board_key(to, p);
+        let num = self.hands[c.index()].num(pt);
         self.hands[c.index()].decrement(pt);
+        key ^= zobrist::hand_key(c, pt, num) ^ zobrist::hand_key(c, pt, num - 1);
+        key ^= zobrist::SIDE;
         let checkers = if self.king(!c).map_or(false, |sq| {
             !(ATTACK_TABLE.attack(pt, to, c, &self.occupied()) & sq).is_empty()
         }) {
@@ -210,7 +600,15 @@ impl Position {
         } else {
             Bitboard::ZERO
         };
-        State::new(checkers, State::calculate_pinned(&self.c_bb, &self.pt_bb))
+        let (pinned, pinners, discovered_check_candidates) =
+            State::calculate_pin_info(&self.c_bb, &self.pt_bb);
+        State::new(
+            checkers,
+            pinned,
+            pinners,
+            discovered_check_candidates,
+            key,
+        )
     }
     fn state(&self) -> Option<&State> {
         self.states.last()
@@ -251,6 +649,108 @@ impl Position {
     }
 }
 
+/// Zobrist hashによる局面ハッシュ計算
+///
+/// `State::key`をインクリメンタルに更新するための乱数テーブルを保持する。
+/// テーブルは起動の度に変わっても差し支えないので、固定シードから
+/// 決定的に生成することでテーブルの持ち運びを不要にしている。
+mod zobrist {
+    use super::{Color, Hand, Piece, PieceType, Square};
+
+    /// 持ち駒の枚数としてあり得る最大値(歩の最大18枚)
+    const MAX_PIECES_IN_HAND: usize = 18;
+
+    const fn splitmix64(seed: &mut u64) -> u64 {
+        *seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = *seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    const fn board_table() -> [[u64; Piece::NUM]; Square::NUM] {
+        let mut seed = 0x1234_5678_9abc_def0_u64;
+        let mut table = [[0u64; Piece::NUM]; Square::NUM];
+        let mut sq = 0;
+        while sq < Square::NUM {
+            let mut pc = 0;
+            while pc < Piece::NUM {
+                table[sq][pc] = splitmix64(&mut seed);
+                pc += 1;
+            }
+            sq += 1;
+        }
+        table
+    }
+
+    const fn hand_table() -> [[[u64; MAX_PIECES_IN_HAND + 1]; PieceType::NUM_HAND]; Color::NUM] {
+        let mut seed = 0x0fed_cba9_8765_4321_u64;
+        let mut table = [[[0u64; MAX_PIECES_IN_HAND + 1]; PieceType::NUM_HAND]; Color::NUM];
+        let mut c = 0;
+        while c < Color::NUM {
+            let mut i = 0;
+            while i < PieceType::NUM_HAND {
+                let mut n = 0;
+                while n <= MAX_PIECES_IN_HAND {
+                    table[c][i][n] = splitmix64(&mut seed);
+                    n += 1;
+                }
+                i += 1;
+            }
+            c += 1;
+        }
+        table
+    }
+
+    static BOARD: [[u64; Piece::NUM]; Square::NUM] = board_table();
+    static HAND: [[[u64; MAX_PIECES_IN_HAND + 1]; PieceType::NUM_HAND]; Color::NUM] = hand_table();
+    /// 手番を区別するための単一の乱数
+    pub(super) const SIDE: u64 = 0x5bd1_e995_9b64_c2b3;
+
+    fn hand_index(pt: PieceType) -> usize {
+        PieceType::ALL_HAND
+            .iter()
+            .position(|&x| x == pt)
+            .expect("pt must be a piece type that can be held in hand")
+    }
+
+    pub(super) fn board_key(sq: Square, p: Piece) -> u64 {
+        BOARD[sq.index()][p.index()]
+    }
+
+    pub(super) fn hand_key(c: Color, pt: PieceType, num: u8) -> u64 {
+        HAND[c.index()][hand_index(pt)][num as usize]
+    }
+
+    /// 盤上の駒と持ち駒、手番からハッシュ値を計算する
+    pub(super) fn initial_key(
+        board: &[Piece; Square::NUM],
+        hands: &[Hand; Color::NUM],
+        side_to_move: Color,
+    ) -> u64 {
+        let mut key = 0u64;
+        for sq in Square::ALL {
+            let p = board[sq.index()];
+            if p != Piece::EMP {
+                key ^= board_key(sq, p);
+            }
+        }
+        for c in Color::ALL {
+            for &pt in PieceType::ALL_HAND.iter() {
+                // インクリメンタル経路はf(0)^f(n)をXORし続けるので、ここでも
+                // num==0のキー(f(0))を無条件にXORしないと経路によって
+                // 同一局面のkeyが食い違ってしまう。
+                let num = hands[c.index()].num(pt);
+                key ^= hand_key(c, pt, num);
+            }
+        }
+        if side_to_move == Color::White {
+            key ^= SIDE;
+        }
+        key
+    }
+}
+
 impl Default for Position {
     fn default() -> Self {
         #[rustfmt::skip]
@@ -308,6 +808,109 @@ impl fmt::Display for Position {
     }
 }
 
+fn sfen_char_to_color_piece_type(ch: char) -> Option<(Color, PieceType)> {
+    let c = if ch.is_ascii_uppercase() {
+        Color::Black
+    } else {
+        Color::White
+    };
+    let pt = match ch.to_ascii_uppercase() {
+        'P' => PieceType::FU,
+        'L' => PieceType::KY,
+        'N' => PieceType::KE,
+        'S' => PieceType::GI,
+        'G' => PieceType::KI,
+        'B' => PieceType::KA,
+        'R' => PieceType::HI,
+        'K' => PieceType::OU,
+        _ => return None,
+    };
+    Some((c, pt))
+}
+
+fn promote_piece_type(pt: PieceType) -> Option<PieceType> {
+    Some(match pt {
+        PieceType::FU => PieceType::TO,
+        PieceType::KY => PieceType::NY,
+        PieceType::KE => PieceType::NK,
+        PieceType::GI => PieceType::NG,
+        PieceType::KA => PieceType::UM,
+        PieceType::HI => PieceType::RY,
+        _ => return None,
+    })
+}
+
+fn sfen_char_to_piece(ch: char, promoted: bool) -> Option<Piece> {
+    let (c, pt) = sfen_char_to_color_piece_type(ch)?;
+    let pt = if promoted { promote_piece_type(pt)? } else { pt };
+    Some(piece_from_color_piece_type(c, pt))
+}
+
+fn piece_from_color_piece_type(c: Color, pt: PieceType) -> Piece {
+    match (c, pt) {
+        (Color::Black, PieceType::FU) => Piece::BFU,
+        (Color::White, PieceType::FU) => Piece::WFU,
+        (Color::Black, PieceType::KY) => Piece::BKY,
+        (Color::White, PieceType::KY) => Piece::WKY,
+        (Color::Black, PieceType::KE) => Piece::BKE,
+        (Color::White, PieceType::KE) => Piece::WKE,
+        (Color::Black, PieceType::GI) => Piece::BGI,
+        (Color::White, PieceType::GI) => Piece::WGI,
+        (Color::Black, PieceType::KI) => Piece::BKI,
+        (Color::White, PieceType::KI) => Piece::WKI,
+        (Color::Black, PieceType::KA) => Piece::BKA,
+        (Color::White, PieceType::KA) => Piece::WKA,
+        (Color::Black, PieceType::HI) => Piece::BHI,
+        (Color::White, PieceType::HI) => Piece::WHI,
+        (Color::Black, PieceType::OU) => Piece::BOU,
+        (Color::White, PieceType::OU) => Piece::WOU,
+        (Color::Black, PieceType::TO) => Piece::BTO,
+        (Color::White, PieceType::TO) => Piece::WTO,
+        (Color::Black, PieceType::NY) => Piece::BNY,
+        (Color::White, PieceType::NY) => Piece::WNY,
+        (Color::Black, PieceType::NK) => Piece::BNK,
+        (Color::White, PieceType::NK) => Piece::WNK,
+        (Color::Black, PieceType::NG) => Piece::BNG,
+        (Color::White, PieceType::NG) => Piece::WNG,
+        (Color::Black, PieceType::UM) => Piece::BUM,
+        (Color::White, PieceType::UM) => Piece::WUM,
+        (Color::Black, PieceType::RY) => Piece::BRY,
+        (Color::White, PieceType::RY) => Piece::WRY,
+        _ => unreachable!("piece type has no board representation"),
+    }
+}
+
+fn piece_to_sfen_str(p: Piece) -> String {
+    let c = p.color().expect("piece must have a color");
+    let pt = p.piece_type().expect("piece must have a piece type");
+    let (base, promoted) = match pt {
+        PieceType::FU => ("P", false),
+        PieceType::KY => ("L", false),
+        PieceType::KE => ("N", false),
+        PieceType::GI => ("S", false),
+        PieceType::KI => ("G", false),
+        PieceType::KA => ("B", false),
+        PieceType::HI => ("R", false),
+        PieceType::OU => ("K", false),
+        PieceType::TO => ("P", true),
+        PieceType::NY => ("L", true),
+        PieceType::NK => ("N", true),
+        PieceType::NG => ("S", true),
+        PieceType::UM => ("B", true),
+        PieceType::RY => ("R", true),
+        _ => unreachable!("piece type has no sfen representation"),
+    };
+    let base = match c {
+        Color::Black => base.to_string(),
+        Color::White => base.to_lowercase(),
+    };
+    if promoted {
+        format!("+{}", base)
+    } else {
+        base
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -386,4 +989,258 @@ mod tests {
         assert_eq!(Color::Black, pos.side_to_move());
         assert!(!pos.in_check());
     }
+
+    #[test]
+    fn test_do_move_capturing_promoted_piece_keeps_key_consistent() {
+        // 後手のと金(成り駒)を先手の銀で取る。持ち駒・zobristは不成の種類
+        // (歩)で管理されるため、インクリメンタルに計算したkeyは捕獲後の
+        // 局面をfrom_sfenで直接構築した場合のkeyと一致しなければならない。
+        let mut pos = Position::from_sfen("4k4/9/9/9/4+p4/4S4/9/9/4K4 b - 1").unwrap();
+        let m = Move::new_normal(Square::SQ56, Square::SQ55, false, Piece::BGI, Piece::WTO);
+        pos.do_move(m);
+        assert_eq!(1, pos.hand(Color::Black).num(PieceType::FU));
+        let expected = Position::from_sfen("4k4/9/9/9/4S4/9/9/9/4K4 w P 1").unwrap();
+        assert_eq!(expected.key(), pos.key());
+        // 取った側の手を戻しても一貫して元のkeyに戻る
+        pos.undo_move(m);
+        let original = Position::from_sfen("4k4/9/9/9/4+p4/4S4/9/9/4K4 b - 1").unwrap();
+        assert_eq!(original.key(), pos.key());
+    }
+
+    #[test]
+    fn test_perft() {
+        let mut pos = Position::default();
+        assert_eq!(30, pos.perft(1));
+        assert_eq!(900, pos.perft(2));
+        assert_eq!(25470, pos.perft(3));
+    }
+
+    #[test]
+    fn test_perft_divide_sums_to_perft() {
+        let mut pos = Position::default();
+        let divided = pos.perft_divide(3);
+        let total: u64 = divided.iter().map(|&(_, nodes)| nodes).sum();
+        assert_eq!(pos.perft(3), total);
+    }
+
+    #[test]
+    fn test_sfen_startpos_round_trip() {
+        let sfen = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
+        let pos = Position::from_sfen(sfen).unwrap();
+        let default = Position::default();
+        assert!(Square::ALL
+            .iter()
+            .all(|&sq| pos.piece_on(sq) == default.piece_on(sq)));
+        assert_eq!(Color::Black, pos.side_to_move());
+        assert_eq!(sfen, pos.to_sfen());
+    }
+
+    #[test]
+    fn test_sfen_hand_and_move_number_round_trip() {
+        let sfen = "4r3k/9/9/9/9/9/9/9/4K4 w 2P3p 50";
+        let pos = Position::from_sfen(sfen).unwrap();
+        assert_eq!(Color::White, pos.side_to_move());
+        assert_eq!(2, pos.hand(Color::Black).num(PieceType::FU));
+        assert_eq!(3, pos.hand(Color::White).num(PieceType::FU));
+        assert_eq!(sfen, pos.to_sfen());
+    }
+
+    #[test]
+    fn test_sfen_in_check_position() {
+        // 5筋が開いていて、後手の飛車が先手玉に王手をかけている局面
+        let pos = Position::from_sfen("4r3k/9/9/9/9/9/9/9/4K4 b - 1").unwrap();
+        assert!(pos.in_check());
+        assert!(!pos.checkers().is_empty());
+    }
+
+    #[test]
+    fn test_sfen_rejects_short_rank() {
+        // 1段目が合計8筋分しかなく不正
+        assert!(Position::from_sfen("8/9/9/9/9/9/9/9/9 b - 1").is_err());
+    }
+
+    #[test]
+    fn test_pinned_and_pinners_basic() {
+        // 先手玉(file5,rank9)と後手飛車(file5,rank1)の間に先手歩(file5,rank5)が
+        // 挟まっており、歩が動くと先手玉に飛車の王手がかかる(ピン)局面
+        let pos = Position::from_sfen("4r3k/9/9/9/4P4/9/9/9/4K4 b - 1").unwrap();
+        assert!(pos.checkers().is_empty());
+        assert_eq!(
+            Bitboard::from_square(Square::SQ51),
+            pos.pinners(Color::Black)
+        );
+        assert_eq!(
+            Bitboard::from_square(Square::SQ55),
+            pos.pinned()[Color::White.index()]
+        );
+        // 挟まっている駒は後手(攻め方)の駒ではないので、discovered checkの
+        // 対象にはならない
+        assert!(pos.discovered_check_candidates(Color::White).is_empty());
+    }
+
+    #[test]
+    fn test_discovered_check_candidates() {
+        // 挟まっている駒(file5,rank5)が後手(攻め方)自身の歩なので、
+        // これを動かすと後手飛車による先手玉への王手が発見される
+        let pos = Position::from_sfen("4r3k/9/9/9/4p4/9/9/9/4K4 b - 1").unwrap();
+        assert_eq!(
+            Bitboard::from_square(Square::SQ55),
+            pos.discovered_check_candidates(Color::White)
+        );
+    }
+
+    #[test]
+    fn test_capture_and_quiet_moves_partition_legal_moves() {
+        let mut pos = Position::default();
+        // 7七・3三の歩を突いて角道を開け、先手角が後手角を取れる形を作る
+        for &m in [
+            Move::new_normal(Square::SQ77, Square::SQ76, false, Piece::BFU, Piece::EMP),
+            Move::new_normal(Square::SQ33, Square::SQ34, false, Piece::WFU, Piece::EMP),
+            Move::new_normal(Square::SQ76, Square::SQ75, false, Piece::BFU, Piece::EMP),
+            Move::new_normal(Square::SQ34, Square::SQ35, false, Piece::WFU, Piece::EMP),
+        ]
+        .iter()
+        {
+            pos.do_move(m);
+        }
+        let captures = pos.capture_moves();
+        let quiets = pos.quiet_moves();
+        let target = pos.pieces_c(!pos.side_to_move());
+        // capture_movesは全て移動先に敵駒があり、quiet_movesは全て敵駒がない
+        assert!(captures
+            .iter()
+            .all(|m| !(target & m.to()).is_empty()));
+        assert!(quiets.iter().all(|m| (target & m.to()).is_empty()));
+        // 合わせると合法手全体と一致し、重複や漏れがない
+        let legals: Vec<Move> = pos.legal_moves().iter().copied().collect();
+        assert_eq!(legals.len(), captures.len() + quiets.len());
+        for m in legals.iter() {
+            assert!(captures.contains(m) != quiets.contains(m));
+        }
+    }
+
+    #[test]
+    fn test_capture_and_quiet_moves_matches_separate_calls() {
+        let mut pos = Position::default();
+        for &m in [
+            Move::new_normal(Square::SQ77, Square::SQ76, false, Piece::BFU, Piece::EMP),
+            Move::new_normal(Square::SQ33, Square::SQ34, false, Piece::WFU, Piece::EMP),
+            Move::new_normal(Square::SQ76, Square::SQ75, false, Piece::BFU, Piece::EMP),
+            Move::new_normal(Square::SQ34, Square::SQ35, false, Piece::WFU, Piece::EMP),
+        ]
+        .iter()
+        {
+            pos.do_move(m);
+        }
+        let (captures, quiets) = pos.capture_and_quiet_moves();
+        // legal_moves()は決定的な順序で生成されるため、同じフィルタ条件で
+        // 個別に呼んだ場合と順序まで一致するはず
+        assert_eq!(pos.capture_moves(), captures);
+        assert_eq!(pos.quiet_moves(), quiets);
+    }
+
+    #[test]
+    fn test_repetition_none_before_any_repeat() {
+        let pos = Position::default();
+        assert_eq!(Repetition::None, pos.repetition());
+    }
+
+    #[test]
+    fn test_repetition_draw() {
+        // 金を1往復させるだけの、王手を一切伴わない4回目の同一局面
+        let mut pos = Position::default();
+        let cycle = [
+            Move::new_normal(Square::SQ69, Square::SQ68, false, Piece::BKI, Piece::EMP),
+            Move::new_normal(Square::SQ41, Square::SQ42, false, Piece::WKI, Piece::EMP),
+            Move::new_normal(Square::SQ68, Square::SQ69, false, Piece::BKI, Piece::EMP),
+            Move::new_normal(Square::SQ42, Square::SQ41, false, Piece::WKI, Piece::EMP),
+        ];
+        for _ in 0..3 {
+            for &m in cycle.iter() {
+                pos.do_move(m);
+            }
+        }
+        assert_eq!(Repetition::Draw, pos.repetition());
+    }
+
+    // 千日手の連続王手判定はState::checkers/keyの組み合わせだけに依存するため、
+    // 実際の指し手を何十手も積み上げる代わりに、同一モジュール内の特権で
+    // statesを直接組み立ててアルゴリズムそのものを検証する。
+    fn checkers_bb(present: bool) -> Bitboard {
+        if present {
+            Bitboard::from_square(Square::SQ51)
+        } else {
+            Bitboard::ZERO
+        }
+    }
+
+    fn repetition_cycle_position(us_checks: [bool; 3], them_checks: [bool; 3]) -> Position {
+        let mut pos = Position::default();
+        let base_key = pos.key();
+        let other_key = base_key ^ 1;
+        pos.states = vec![
+            State::new(
+                Bitboard::ZERO,
+                [Bitboard::ZERO; Color::NUM],
+                [Bitboard::ZERO; Color::NUM],
+                [Bitboard::ZERO; Color::NUM],
+                base_key,
+            ),
+            State::new(
+                checkers_bb(us_checks[0]),
+                [Bitboard::ZERO; Color::NUM],
+                [Bitboard::ZERO; Color::NUM],
+                [Bitboard::ZERO; Color::NUM],
+                other_key,
+            ),
+            State::new(
+                checkers_bb(them_checks[0]),
+                [Bitboard::ZERO; Color::NUM],
+                [Bitboard::ZERO; Color::NUM],
+                [Bitboard::ZERO; Color::NUM],
+                base_key,
+            ),
+            State::new(
+                checkers_bb(us_checks[1]),
+                [Bitboard::ZERO; Color::NUM],
+                [Bitboard::ZERO; Color::NUM],
+                [Bitboard::ZERO; Color::NUM],
+                other_key,
+            ),
+            State::new(
+                checkers_bb(them_checks[1]),
+                [Bitboard::ZERO; Color::NUM],
+                [Bitboard::ZERO; Color::NUM],
+                [Bitboard::ZERO; Color::NUM],
+                base_key,
+            ),
+            State::new(
+                checkers_bb(us_checks[2]),
+                [Bitboard::ZERO; Color::NUM],
+                [Bitboard::ZERO; Color::NUM],
+                [Bitboard::ZERO; Color::NUM],
+                other_key,
+            ),
+            State::new(
+                checkers_bb(them_checks[2]),
+                [Bitboard::ZERO; Color::NUM],
+                [Bitboard::ZERO; Color::NUM],
+                [Bitboard::ZERO; Color::NUM],
+                base_key,
+            ),
+        ];
+        pos
+    }
+
+    #[test]
+    fn test_repetition_lose_by_perpetual_check() {
+        let pos = repetition_cycle_position([true, true, true], [false, false, false]);
+        assert_eq!(Repetition::LoseByPerpetualCheck, pos.repetition());
+    }
+
+    #[test]
+    fn test_repetition_win_by_perpetual_check() {
+        let pos = repetition_cycle_position([false, false, false], [true, true, true]);
+        assert_eq!(Repetition::WinByPerpetualCheck, pos.repetition());
+    }
 }